@@ -1,21 +1,136 @@
-use std::collections::HashMap;
-use std::fs::File;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, FileTimes};
 use std::io::Write;
 use std::time::{Duration, SystemTime};
 
+#[derive(Serialize, Deserialize, Clone)]
+struct TimeEntry {
+    start_time: SystemTime,
+    end_time: Option<SystemTime>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 struct Task {
     id: u32,
     name: String,
-    start_time: SystemTime,
-    end_time: SystemTime,
+    entries: Vec<TimeEntry>,
+    tags: HashSet<String>,
+    priority: Priority,
+    due_date: Option<SystemTime>,
+}
+
+/// Backend responsible for persisting tracker state. `FileStorage` is the
+/// default. The trait is crate-private, so only code inside this crate (such
+/// as the tests) can supply another backend.
+pub(crate) trait Storage {
+    /// Persist the full task set, replacing any previously stored state.
+    fn save_all(&self, tasks: &[Task]) -> Result<(), std::io::Error>;
+    /// Persist the next task id so it survives a restart.
+    fn write_cache(&self, id: u32) -> Result<(), std::io::Error>;
+    /// Load every persisted task. Returns an empty vec when nothing is stored.
+    fn load_all(&self) -> Vec<Task>;
+    /// Read the cached next id, if one was written.
+    fn read_cache(&self) -> Option<u32>;
+    /// Remove all persisted state.
+    fn clear(&self) -> Result<(), std::io::Error>;
+}
+
+struct FileStorage {
+    save_file_path: String,
+    cache_file_path: String,
+}
+
+impl FileStorage {
+    /// Write `contents` to `path` atomically while holding an advisory lock.
+    ///
+    /// The lock only serializes the physical write, so two processes cannot
+    /// interleave partial writes; it does *not* guard against a lost update
+    /// where both loaded stale state before either wrote. The temp-file plus
+    /// rename guarantees a reader never observes a half-written file.
+    fn atomic_write(&self, path: &str, contents: &str) -> Result<(), std::io::Error> {
+        let lock_path = format!("{}.lock", path);
+        let lock = File::options()
+            .write(true)
+            .truncate(false)
+            .create(true)
+            .open(&lock_path)?;
+        lock.lock()?;
+
+        let tmp_path = format!("{}.tmp", path);
+        let mut tmp = File::options()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .open(&tmp_path)?;
+        write!(&mut tmp, "{}", contents)?;
+        tmp.flush()?;
+
+        // Stamp the save instant onto the file so external readers see a
+        // deliberate modified time rather than an arbitrary one.
+        let now = SystemTime::now();
+        tmp.set_times(FileTimes::new().set_accessed(now).set_modified(now))?;
+
+        std::fs::rename(&tmp_path, path)?;
+        lock.unlock()?;
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    fn save_all(&self, tasks: &[Task]) -> Result<(), std::io::Error> {
+        let json = serde_json::to_string_pretty(tasks)
+            .expect("Failed to serialize tasks");
+        self.atomic_write(&self.save_file_path, &json)
+    }
+
+    fn write_cache(&self, id: u32) -> Result<(), std::io::Error> {
+        self.atomic_write(&self.cache_file_path, &format!("{}\n", id))
+    }
+
+    fn load_all(&self) -> Vec<Task> {
+        if !std::path::Path::new(&self.save_file_path).exists() {
+            return Vec::new();
+        }
+        let content = std::fs::read_to_string(&self.save_file_path)
+            .expect("Failed to read save file");
+        serde_json::from_str(&content).unwrap_or_default()
+    }
+
+    fn read_cache(&self) -> Option<u32> {
+        if !std::path::Path::new(&self.cache_file_path).exists() {
+            return None;
+        }
+        let cache_content = std::fs::read_to_string(&self.cache_file_path)
+            .expect("Failed to read cache file");
+        cache_content.trim().parse().ok()
+    }
+
+    fn clear(&self) -> Result<(), std::io::Error> {
+        if std::path::Path::new(&self.save_file_path).exists() {
+            std::fs::remove_file(&self.save_file_path)?;
+        }
+        if std::path::Path::new(&self.cache_file_path).exists() {
+            std::fs::remove_file(&self.cache_file_path)?;
+        }
+        Ok(())
+    }
 }
 
 pub struct TimeTracer {
     current_id: u32,
     tasks_map: HashMap<u32, Task>,
     running_tasks: Vec<u32>,
-    save_file_path: String,
-    cache_file_path: String,
+    storage: Box<dyn Storage>,
+    last_saved: Option<SystemTime>,
 }
 
 impl TimeTracer {
@@ -24,48 +139,76 @@ impl TimeTracer {
     }
 
     fn new_with_file_path(save_file_path: &str, cache_file_path: &str) -> TimeTracer {
-        // get current id from the cache file
-        let mut current_id = 0;
-        if std::path::Path::new(cache_file_path).exists() {
-            let cache_content = std::fs::read_to_string(cache_file_path)
-                .expect("Failed to read cache file");
-            current_id = cache_content.trim()
-                .parse()
-                .expect("Failed to parse cache file");
-        }
+        let storage = FileStorage {
+            save_file_path: save_file_path.to_string(),
+            cache_file_path: cache_file_path.to_string(),
+        };
+        Self::new_with_storage(Box::new(storage))
+    }
+
+    fn new_with_storage(storage: Box<dyn Storage>) -> TimeTracer {
+        // repopulate from the real saved data; derive the running set from the
+        // tasks that still have an open entry.
+        let tasks = storage.load_all();
+        let running_tasks: Vec<u32> = tasks
+            .iter()
+            .filter(|task| task.entries.iter().any(|e| e.end_time.is_none()))
+            .map(|task| task.id)
+            .collect();
+        let max_id = tasks.iter().map(|task| task.id + 1).max().unwrap_or(0);
+        let tasks_map: HashMap<u32, Task> =
+            tasks.into_iter().map(|task| (task.id, task)).collect();
+
+        // The cache is authoritative for the next id, so tasks created after
+        // the last save (and thus absent from the save file) do not get their
+        // ids reused. Should the cache be lost while the save survives, fall
+        // back to the saved data and never hand out an id at or below one
+        // already on disk.
+        let current_id = storage.read_cache().unwrap_or(0).max(max_id);
 
         TimeTracer {
             current_id,
-            tasks_map: HashMap::new(),
-            running_tasks: Vec::new(),
-            save_file_path: save_file_path.to_string(),
-            cache_file_path: cache_file_path.to_string(),
+            tasks_map,
+            running_tasks,
+            storage,
+            last_saved: None,
         }
     }
 
-    pub fn delete_save_files(&mut self) -> Result<(), std::io::Error> {
-        if !std::path::Path::new(&self.save_file_path).exists() {
-            return Ok(());
-        }
-        std::fs::remove_file(&self.save_file_path)?;
+    /// The instant of the most recent successful save, if any. Callers can use
+    /// it to detect state that has gone stale relative to another writer.
+    pub fn last_saved(&self) -> Option<SystemTime> {
+        self.last_saved
+    }
 
+    pub fn delete_save_files(&mut self) -> Result<(), std::io::Error> {
+        self.storage.clear()?;
         self.current_id = 0;
         Ok(())
     }
 
-    pub fn new_task(&mut self, name: &str) -> u32 {
+    pub fn new_task(
+        &mut self,
+        name: &str,
+        tags: HashSet<String>,
+        priority: Priority,
+        due_date: Option<SystemTime>,
+    ) -> u32 {
         let task = Task {
             id: self.current_id,
             name: name.to_string(),
-            start_time: SystemTime::now(),
-            end_time: SystemTime::now(),
+            entries: Vec::new(),
+            tags,
+            priority,
+            due_date,
         };
 
         let id = self.current_id;
         self.tasks_map.insert(self.current_id, task);
         self.current_id += 1;
 
-        Self::save_cache_to_file(self.current_id, &self.cache_file_path)
+        self.storage
+            .write_cache(self.current_id)
             .expect("Failed to save cache to file");
 
         id
@@ -75,6 +218,72 @@ impl TimeTracer {
         self.tasks_map.len() as u32
     }
 
+    pub fn get_total_duration(&self, id: u32) -> Option<Duration> {
+        self.tasks_map.get(&id).map(Self::total_duration)
+    }
+
+    pub fn tasks_with_tag(&self, tag: &str) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .tasks_map
+            .values()
+            .filter(|task| task.tags.contains(tag))
+            .map(|task| task.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn tasks_by_priority(&self, priority: Priority) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .tasks_map
+            .values()
+            .filter(|task| task.priority == priority)
+            .map(|task| task.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    pub fn report_total(&self) -> Duration {
+        self.tasks_map.values().map(Self::total_duration).sum()
+    }
+
+    pub fn report_by_tag(&self) -> HashMap<String, Duration> {
+        let mut report: HashMap<String, Duration> = HashMap::new();
+        for task in self.tasks_map.values() {
+            let duration = Self::total_duration(task);
+            for tag in &task.tags {
+                *report.entry(tag.clone()).or_default() += duration;
+            }
+        }
+        report
+    }
+
+    pub fn report_by_day(&self) -> BTreeMap<NaiveDate, Duration> {
+        let mut report: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+        for task in self.tasks_map.values() {
+            for entry in &task.entries {
+                if let Some(end) = entry.end_time {
+                    let duration = end.duration_since(entry.start_time).expect("Time went backwards");
+                    let date = DateTime::<Utc>::from(entry.start_time).date_naive();
+                    *report.entry(date).or_default() += duration;
+                }
+            }
+        }
+        report
+    }
+
+    // sum the closed entries of a task
+    fn total_duration(task: &Task) -> Duration {
+        task.entries
+            .iter()
+            .filter_map(|e| {
+                e.end_time
+                    .map(|end| end.duration_since(e.start_time).expect("Time went backwards"))
+            })
+            .sum()
+    }
+
     pub fn start_task(&mut self, id: u32) -> bool {
         if self.running_tasks.contains(&id) {
             return false;
@@ -84,7 +293,10 @@ impl TimeTracer {
         match task {
             None => false,
             Some(task) => {
-                task.start_time = SystemTime::now();
+                task.entries.push(TimeEntry {
+                    start_time: SystemTime::now(),
+                    end_time: None,
+                });
                 self.running_tasks.push(id);
                 true
             }
@@ -96,56 +308,73 @@ impl TimeTracer {
             return None;
         }
 
-        let task = self.tasks_map.get_mut(&id);
-        match task {
-            None => None,
+        let duration = match self.tasks_map.get_mut(&id) {
+            None => return None,
             Some(task) => {
-                task.end_time = SystemTime::now();
-                self.running_tasks.retain(|&x| x != id);
-                let duration = Some(task.end_time.duration_since(task.start_time).expect("Time went backwards"));
-
-                let write_result = Self::save_task_to_file(task, &self.save_file_path);
-                if write_result.is_err() {
-                    eprintln!("Failed to write to file: {}", write_result.err().unwrap());
+                // close the most recent open entry
+                if let Some(entry) = task.entries.iter_mut().rev().find(|e| e.end_time.is_none()) {
+                    entry.end_time = Some(SystemTime::now());
                 }
-                duration
+                Self::total_duration(task)
             }
-        }
-    }
-
-    fn save_task_to_file(task: &Task, file_path: &str) -> Result<(), std::io::Error> {
-        let mut file = File::options()
-            .append(true)
-            .create(true)
-            .open(file_path)?;
+        };
+        self.running_tasks.retain(|&x| x != id);
 
-        let start_time = task.start_time.duration_since(SystemTime::UNIX_EPOCH).expect("Time went backwards");
-        let end_time = task.end_time.duration_since(SystemTime::UNIX_EPOCH).expect("Time went backwards");
-        writeln!(&mut file, "{},{},{},{}", task.id, task.name, start_time.as_secs(), end_time.as_secs())?;
-        Ok(())
+        let tasks: Vec<Task> = self.tasks_map.values().cloned().collect();
+        match self.storage.save_all(&tasks) {
+            Ok(()) => self.last_saved = Some(SystemTime::now()),
+            Err(err) => eprintln!("Failed to write to file: {}", err),
+        }
+        Some(duration)
     }
+}
 
-    fn save_cache_to_file(id_to_save: u32, file_path: &str) -> Result<(), std::io::Error> {
-        let mut file = File::options()
-            .write(true)
-            .create(true)
-            .open(file_path)?;
-        writeln!(&mut file, "{}", id_to_save)?;
-        Ok(())
-    }
+/// Render a `Duration` as a compact `"1h 23m"` string for display.
+pub fn format_duration(duration: Duration) -> String {
+    let total_minutes = duration.as_secs() / 60;
+    let hours = total_minutes / 60;
+    let minutes = total_minutes % 60;
+    format!("{}h {}m", hours, minutes)
 }
 
 #[cfg(test)]
-use regex::Regex;
-
 mod tests {
     use super::*;
 
     const DUMMY_SAVE_FILE_PATH: &str = "test_work/dummy_save.txt";
     const DUMMY_CACHE_FILE_PATH: &str = "test_work/dummy_cache.txt";
 
+    // in-memory backend so tests need not touch the filesystem
+    #[derive(Default)]
+    struct MemoryStorage {
+        tasks: std::cell::RefCell<Vec<Task>>,
+        cache: std::cell::RefCell<Option<u32>>,
+    }
+
+    impl Storage for MemoryStorage {
+        fn save_all(&self, tasks: &[Task]) -> Result<(), std::io::Error> {
+            *self.tasks.borrow_mut() = tasks.to_vec();
+            Ok(())
+        }
+        fn write_cache(&self, id: u32) -> Result<(), std::io::Error> {
+            *self.cache.borrow_mut() = Some(id);
+            Ok(())
+        }
+        fn load_all(&self) -> Vec<Task> {
+            self.tasks.borrow().clone()
+        }
+        fn read_cache(&self) -> Option<u32> {
+            *self.cache.borrow()
+        }
+        fn clear(&self) -> Result<(), std::io::Error> {
+            self.tasks.borrow_mut().clear();
+            *self.cache.borrow_mut() = None;
+            Ok(())
+        }
+    }
+
     fn create_task_and_start_end(tracer: &mut TimeTracer, task_name: &str) -> bool {
-        let id = tracer.new_task(task_name);
+        let id = tracer.new_task(task_name, HashSet::new(), Priority::Medium, None);
         let start_result = tracer.start_task(id);
         if !start_result {
             return false;
@@ -165,7 +394,7 @@ mod tests {
 
         assert_eq!(tracer.get_task_number(), 0);
 
-        tracer.new_task("task1");
+        tracer.new_task("task1", HashSet::new(), Priority::Medium, None);
         assert_eq!(tracer.get_task_number(), 1);
     }
 
@@ -179,14 +408,14 @@ mod tests {
 
         tracer.delete_save_files().unwrap();
 
-        tracer.new_task("task1");
+        tracer.new_task("task1", HashSet::new(), Priority::Medium, None);
 
         // check if the cache file contains the correct number
         assert!(std::path::Path::new(cache_file_path).exists());
         let cache_content = std::fs::read_to_string(cache_file_path).unwrap();
         assert_eq!(cache_content, "1\n");
 
-        tracer.new_task("task2");
+        tracer.new_task("task2", HashSet::new(), Priority::Medium, None);
         let cache_content = std::fs::read_to_string(cache_file_path).unwrap();
         assert_eq!(cache_content, "2\n");
     }
@@ -198,7 +427,7 @@ mod tests {
             DUMMY_CACHE_FILE_PATH,
         );
 
-        let id = tracer.new_task("task1");
+        let id = tracer.new_task("task1", HashSet::new(), Priority::Medium, None);
         assert_eq!(tracer.start_task(id), true);
     }
 
@@ -209,7 +438,7 @@ mod tests {
             DUMMY_CACHE_FILE_PATH,
         );
 
-        let id = tracer.new_task("task1");
+        let id = tracer.new_task("task1", HashSet::new(), Priority::Medium, None);
         tracer.start_task(id);
 
         // Sleep for 0.5 seconds and check if the duration is greater than 0.5 seconds
@@ -225,7 +454,7 @@ mod tests {
             DUMMY_CACHE_FILE_PATH,
         );
 
-        let id = tracer.new_task("task1");
+        let id = tracer.new_task("task1", HashSet::new(), Priority::Medium, None);
         tracer.start_task(id);
         assert_eq!(tracer.start_task(id), false);
     }
@@ -237,7 +466,7 @@ mod tests {
             DUMMY_CACHE_FILE_PATH,
         );
 
-        let id = tracer.new_task("task1");
+        let id = tracer.new_task("task1", HashSet::new(), Priority::Medium, None);
         assert_eq!(tracer.end_task(id), None);
     }
 
@@ -258,10 +487,10 @@ mod tests {
         // check if the file is created
         assert!(std::path::Path::new(file_path).exists());
 
-        // check content of the file using regex
-        let file_content = std::fs::read_to_string(file_path).unwrap();
-        let re = Regex::new(r"0,task1,\d+,\d+").unwrap();
-        assert!(re.is_match(&file_content));
+        // reloading from the save file should recover the task
+        let reloaded = TimeTracer::new_with_file_path(file_path, cache_file_path);
+        assert_eq!(reloaded.get_task_number(), 1);
+        assert!(reloaded.get_total_duration(0).unwrap().subsec_millis() >= 500);
     }
 
     #[test]
@@ -281,13 +510,82 @@ mod tests {
         // create a new task and write it again
         assert!(create_task_and_start_end(&mut tracer, "task2"));
 
-        // check line number of the file
-        let file_content = std::fs::read_to_string(file_path).unwrap();
-        assert_eq!(file_content.lines().count(), 2);
+        // reloading should recover both tasks with their names intact
+        let reloaded = TimeTracer::new_with_file_path(file_path, cache_file_path);
+        assert_eq!(reloaded.get_task_number(), 2);
+        assert_eq!(reloaded.tasks_map.get(&1).unwrap().name, "task2");
+    }
+
+    #[test]
+    fn if_metadata_set_then_queries_filter_tasks() {
+        let mut tracer = TimeTracer::new_with_storage(Box::new(MemoryStorage::default()));
+
+        let mut work_tags = HashSet::new();
+        work_tags.insert("work".to_string());
+        let a = tracer.new_task("task1", work_tags, Priority::High, None);
+        let b = tracer.new_task("task2", HashSet::new(), Priority::Low, None);
+
+        assert_eq!(tracer.tasks_with_tag("work"), vec![a]);
+        assert!(tracer.tasks_with_tag("missing").is_empty());
+        assert_eq!(tracer.tasks_by_priority(Priority::High), vec![a]);
+        assert_eq!(tracer.tasks_by_priority(Priority::Low), vec![b]);
+    }
+
+    #[test]
+    fn if_tasks_recorded_then_reports_aggregate() {
+        let mut tracer = TimeTracer::new_with_storage(Box::new(MemoryStorage::default()));
+
+        let mut work_tags = HashSet::new();
+        work_tags.insert("work".to_string());
+        let id = tracer.new_task("task1", work_tags, Priority::Medium, None);
+        tracer.start_task(id);
+        std::thread::sleep(Duration::from_millis(500));
+        tracer.end_task(id);
+
+        assert!(tracer.report_total().subsec_millis() >= 500);
+        assert!(tracer.report_by_tag().get("work").unwrap().subsec_millis() >= 500);
+        assert_eq!(tracer.report_by_day().len(), 1);
+    }
+
+    #[test]
+    fn if_in_memory_storage_then_state_round_trips() {
+        let mut tracer = TimeTracer::new_with_storage(Box::new(MemoryStorage::default()));
+        assert!(create_task_and_start_end(&mut tracer, "task1"));
+
+        // rebuild a tracer from the same backend to check the saved state
+        let reloaded = TimeTracer::new_with_storage(tracer.storage);
+        assert!(reloaded.get_total_duration(0).unwrap().subsec_millis() >= 500);
+    }
+
+    #[test]
+    fn if_task_saved_then_last_saved_is_set() {
+        let file_path = "test_work/test_save_last_saved.txt";
+        let cache_file_path = "test_work/test_cache_last_saved.txt";
+        let mut tracer = TimeTracer::new_with_file_path(file_path, cache_file_path);
+
+        assert!(tracer.delete_save_files().is_ok());
+        assert!(tracer.last_saved().is_none());
+
+        assert!(create_task_and_start_end(&mut tracer, "task1"));
+        assert!(tracer.last_saved().is_some());
+    }
+
+    #[test]
+    fn if_duration_formatted_then_human_readable() {
+        assert_eq!(format_duration(Duration::from_secs(83 * 60)), "1h 23m");
+        assert_eq!(format_duration(Duration::from_secs(0)), "0h 0m");
+    }
+
+    #[test]
+    fn if_name_contains_comma_then_round_trips() {
+        let file_path = "test_work/test_save_comma.txt";
+        let cache_file_path = "test_work/test_cache_comma.txt";
+        let mut tracer = TimeTracer::new_with_file_path(file_path, cache_file_path);
+
+        assert!(tracer.delete_save_files().is_ok());
+        assert!(create_task_and_start_end(&mut tracer, "fix bug, then ship"));
 
-        // check last line
-        let last_line = file_content.lines().last().unwrap();
-        let re = Regex::new(r"1,task2,\d+,\d+").unwrap();
-        assert!(re.is_match(last_line));
+        let reloaded = TimeTracer::new_with_file_path(file_path, cache_file_path);
+        assert_eq!(reloaded.tasks_map.get(&0).unwrap().name, "fix bug, then ship");
     }
 }